@@ -0,0 +1,158 @@
+use dashmap::DashMap;
+use lib_ot::core::Delta;
+use lib_ot::rich_text::RichTextAttributes;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+pub(crate) type RichTextDelta = Delta<RichTextAttributes>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl CursorRange {
+    fn is_collapsed(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// A remote user's cursor/selection, expressed against a specific `rev_id`.
+///
+/// The `rev_id` is the revision the *sender* had applied when the position was captured; it may
+/// be behind the revisions the local editor has already composed, so the offsets must be
+/// transformed forward before they are safe to render.
+#[derive(Debug, Clone)]
+pub struct CursorState {
+    pub user_id: String,
+    pub rev_id: i64,
+    pub range: CursorRange,
+}
+
+/// Sibling of `ServerRevisionWSData` carrying presence instead of document content. It is keyed
+/// by `object_id` + `user_id` + `rev_id` the same way a revision is keyed by `object_id` +
+/// `rev_id`, so `FlowyDocumentManager::receive_ws_cursor_data` can route it to the same
+/// per-document receiver that `receive_ws_data` uses.
+#[derive(Debug, Clone)]
+pub struct CursorWSData {
+    pub object_id: String,
+    pub cursor: CursorState,
+}
+
+/// Tracks the other users currently looking at one document and keeps their cursors aligned with
+/// the local revision head.
+pub(crate) struct CursorController {
+    #[allow(dead_code)]
+    doc_id: String,
+    cursors: DashMap<String, CursorState>,
+    notifier: broadcast::Sender<CursorState>,
+}
+
+impl CursorController {
+    pub(crate) fn new(doc_id: &str) -> Arc<Self> {
+        let (notifier, _) = broadcast::channel(100);
+        Arc::new(Self {
+            doc_id: doc_id.to_owned(),
+            cursors: DashMap::new(),
+            notifier,
+        })
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<CursorState> {
+        self.notifier.subscribe()
+    }
+
+    /// Transforms `cursor` forward through every delta the local editor has composed since
+    /// `cursor.rev_id`, using the same OT transform the editor applies to its own deltas, then
+    /// stores and broadcasts the result.
+    pub(crate) fn receive_cursor(&self, cursor: CursorState, unapplied_deltas: &[RichTextDelta]) {
+        let mut range = cursor.range.clone();
+        for delta in unapplied_deltas {
+            range = transform_range(delta, &range);
+        }
+
+        let transformed = CursorState { range, ..cursor };
+        self.cursors.insert(transformed.user_id.clone(), transformed.clone());
+        let _ = self.notifier.send(transformed);
+    }
+
+    pub(crate) fn remove_user(&self, user_id: &str) {
+        self.cursors.remove(user_id);
+    }
+
+    pub(crate) fn contains_user(&self, user_id: &str) -> bool {
+        self.cursors.contains_key(user_id)
+    }
+
+    pub(crate) fn clear(&self) {
+        self.cursors.clear();
+    }
+}
+
+fn transform_range(delta: &RichTextDelta, range: &CursorRange) -> CursorRange {
+    let start = delta.transform_offset(range.start);
+    if range.is_collapsed() {
+        CursorRange { start, end: start }
+    } else {
+        CursorRange {
+            start,
+            end: delta.transform_offset(range.end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(user_id: &str, rev_id: i64, start: usize, end: usize) -> CursorState {
+        CursorState {
+            user_id: user_id.to_owned(),
+            rev_id,
+            range: CursorRange { start, end },
+        }
+    }
+
+    #[test]
+    fn receive_cursor_with_no_unapplied_deltas_stores_and_broadcasts_unchanged() {
+        let controller = CursorController::new("doc-1");
+        let mut subscription = controller.subscribe();
+
+        controller.receive_cursor(cursor("user-1", 4, 2, 2), &[]);
+
+        let broadcast = subscription.try_recv().expect("receive_cursor should broadcast");
+        assert_eq!(broadcast.user_id, "user-1");
+        assert_eq!(broadcast.range, CursorRange { start: 2, end: 2 });
+        assert!(controller.contains_user("user-1"));
+    }
+
+    #[test]
+    fn remove_user_drops_only_that_users_cursor() {
+        let controller = CursorController::new("doc-1");
+        controller.receive_cursor(cursor("user-1", 1, 0, 0), &[]);
+        controller.receive_cursor(cursor("user-2", 1, 3, 3), &[]);
+
+        controller.remove_user("user-1");
+
+        assert!(!controller.cursors.contains_key("user-1"));
+        assert!(controller.cursors.contains_key("user-2"));
+    }
+
+    #[test]
+    fn clear_drops_every_cursor() {
+        let controller = CursorController::new("doc-1");
+        controller.receive_cursor(cursor("user-1", 1, 0, 0), &[]);
+        controller.receive_cursor(cursor("user-2", 1, 3, 3), &[]);
+
+        controller.clear();
+
+        assert!(controller.cursors.is_empty());
+    }
+
+    #[test]
+    fn is_collapsed_reflects_equal_bounds() {
+        assert!(CursorRange { start: 5, end: 5 }.is_collapsed());
+        assert!(!CursorRange { start: 5, end: 6 }.is_collapsed());
+    }
+}
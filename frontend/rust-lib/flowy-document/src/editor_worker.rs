@@ -0,0 +1,244 @@
+use crate::{
+    cursor::{CursorController, CursorState},
+    editor::ClientDocumentEditor,
+    errors::FlowyError,
+    manager::DocumentWSReceiver,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use flowy_collaboration::entities::{revision::Revision, ws_data::ServerRevisionWSData};
+use flowy_error::FlowyResult;
+use lib_ws::WSConnectState;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Every mutation of a `ClientDocumentEditor`'s document state - a locally authored delta, a
+/// remote revision, or a request for the current JSON snapshot - becomes a message on this
+/// channel. The worker task reading it is the editor's sole mutator, so OT composition can never
+/// race between the local-edit path and the WS-receive path.
+pub(crate) enum EditorCommand {
+    ComposeLocal {
+        delta: Bytes,
+        ret: oneshot::Sender<FlowyResult<()>>,
+    },
+    ApplyRemote {
+        data: ServerRevisionWSData,
+        ret: oneshot::Sender<FlowyResult<()>>,
+    },
+    ApplyRevisions {
+        revisions: Vec<Revision>,
+        ret: oneshot::Sender<FlowyResult<()>>,
+    },
+    Snapshot {
+        ret: oneshot::Sender<FlowyResult<String>>,
+    },
+    Stop,
+}
+
+pub(crate) struct EditorWorker {
+    editor: Arc<ClientDocumentEditor>,
+    cursor_controller: Arc<CursorController>,
+    sender: mpsc::Sender<EditorCommand>,
+    // `Mutex` instead of a bare `JoinHandle` so `stop()` can take it out and await it through `&self`
+    // - every `Arc<EditorWorker>` clone shares the same handle, so whichever clone calls `stop()`
+    // joins the real task.
+    join_handle: Mutex<Option<tokio::task::JoinHandle<()>>>,
+}
+
+impl EditorWorker {
+    pub(crate) fn spawn(
+        doc_id: &str,
+        editor: Arc<ClientDocumentEditor>,
+        cancel_token: CancellationToken,
+        cursor_controller: Arc<CursorController>,
+    ) -> Self {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let worker_editor = editor.clone();
+        let doc_id = doc_id.to_owned();
+        let join_handle = tokio::spawn(async move {
+            loop {
+                let command = match next_command(&mut receiver, &cancel_token).await {
+                    NextCommand::Cancelled => {
+                        tracing::trace!("{} editor worker cancelled, exiting without mutating cache", doc_id);
+                        break;
+                    }
+                    NextCommand::Closed => break,
+                    NextCommand::Received(command) => command,
+                };
+
+                match command {
+                    EditorCommand::ComposeLocal { delta, ret } => {
+                        let result = worker_editor.compose_local_delta(delta).await.map(|_| ());
+                        let _ = ret.send(result);
+                    }
+                    EditorCommand::ApplyRemote { data, ret } => {
+                        let result = worker_editor.ws_handler().receive_ws_data(data).await;
+                        let _ = ret.send(result);
+                    }
+                    EditorCommand::ApplyRevisions { revisions, ret } => {
+                        let result = worker_editor.ws_handler().receive_revisions(revisions).await;
+                        let _ = ret.send(result);
+                    }
+                    EditorCommand::Snapshot { ret } => {
+                        let result = worker_editor.document_json().await;
+                        let _ = ret.send(result);
+                    }
+                    EditorCommand::Stop => break,
+                }
+            }
+            tracing::trace!("{} editor worker stopped", doc_id);
+        });
+
+        Self {
+            editor,
+            cursor_controller,
+            sender,
+            join_handle: Mutex::new(Some(join_handle)),
+        }
+    }
+
+    pub(crate) async fn compose_local_delta(&self, delta: Bytes) -> FlowyResult<()> {
+        let (ret, rx) = oneshot::channel();
+        self.send(EditorCommand::ComposeLocal { delta, ret }).await?;
+        rx.await
+            .map_err(|_| FlowyError::internal().context("editor worker dropped the reply channel"))?
+    }
+
+    pub(crate) async fn document_json(&self) -> FlowyResult<String> {
+        let (ret, rx) = oneshot::channel();
+        self.send(EditorCommand::Snapshot { ret }).await?;
+        rx.await
+            .map_err(|_| FlowyError::internal().context("editor worker dropped the reply channel"))?
+    }
+
+    /// Sends `Stop` and waits for the worker task to actually exit, so every command queued ahead
+    /// of it - a `ComposeLocal` or `ApplyRemote` already in flight - finishes mutating state before
+    /// this returns, instead of being hard-aborted mid-mutation by `Drop`.
+    pub(crate) async fn stop(&self) {
+        let _ = self.sender.send(EditorCommand::Stop).await;
+        let handle = self.join_handle.lock().await.take();
+        if let Some(handle) = handle {
+            let _ = handle.await;
+        }
+    }
+
+    pub(crate) fn editor(&self) -> Arc<ClientDocumentEditor> {
+        self.editor.clone()
+    }
+
+    async fn send(&self, command: EditorCommand) -> FlowyResult<()> {
+        self.sender
+            .send(command)
+            .await
+            .map_err(|_| FlowyError::internal().context("editor worker is no longer running"))
+    }
+}
+
+#[async_trait]
+impl DocumentWSReceiver for EditorWorker {
+    async fn receive_ws_data(&self, data: ServerRevisionWSData) -> Result<(), FlowyError> {
+        let (ret, rx) = oneshot::channel();
+        self.send(EditorCommand::ApplyRemote { data, ret }).await?;
+        rx.await.map_err(|_| FlowyError::internal().context("editor worker dropped the reply channel"))?
+    }
+
+    async fn receive_revisions(&self, revisions: Vec<Revision>) -> Result<(), FlowyError> {
+        let (ret, rx) = oneshot::channel();
+        self.send(EditorCommand::ApplyRevisions { revisions, ret }).await?;
+        rx.await.map_err(|_| FlowyError::internal().context("editor worker dropped the reply channel"))?
+    }
+
+    /// Transforms the cursor through every delta composed locally since `cursor.rev_id` and hands
+    /// it to this document's `CursorController`, which stores it and broadcasts it to subscribers.
+    async fn receive_ws_cursor(&self, cursor: CursorState) -> Result<(), FlowyError> {
+        let unapplied_deltas = self.editor.unapplied_deltas_since(cursor.rev_id).await?;
+        self.cursor_controller.receive_cursor(cursor, &unapplied_deltas);
+        Ok(())
+    }
+
+    fn current_rev_id(&self) -> Option<i64> {
+        Some(self.editor.rev_id())
+    }
+
+    fn connect_state_changed(&self, state: WSConnectState) {
+        self.editor.ws_handler().connect_state_changed(state)
+    }
+}
+
+impl Drop for EditorWorker {
+    /// Safety net only: the normal shutdown path is `stop()`, which already took the handle out
+    /// and joined it, leaving nothing here to abort. This only fires for a worker that was dropped
+    /// without ever being stopped.
+    fn drop(&mut self) {
+        if let Ok(mut guard) = self.join_handle.try_lock() {
+            if let Some(handle) = guard.take() {
+                handle.abort();
+            }
+        }
+    }
+}
+
+enum NextCommand {
+    Received(EditorCommand),
+    Cancelled,
+    Closed,
+}
+
+/// The worker loop's draining decision, extracted so the race between an enqueued command and
+/// cancellation can be exercised without a real `ClientDocumentEditor`. `biased` makes `recv()` win
+/// whenever a command is already queued - `cancel_document_operations` is called before `stop()`
+/// sends `Stop`, so an unbiased select could otherwise drop an in-flight command on the random
+/// tie-break.
+async fn next_command(receiver: &mut mpsc::Receiver<EditorCommand>, cancel_token: &CancellationToken) -> NextCommand {
+    tokio::select! {
+        biased;
+        command = receiver.recv() => match command {
+            Some(command) => NextCommand::Received(command),
+            None => NextCommand::Closed,
+        },
+        _ = cancel_token.cancelled() => NextCommand::Cancelled,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_queued_command_is_drained_even_if_cancellation_already_fired() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let cancel_token = CancellationToken::new();
+        sender.send(EditorCommand::Stop).await.unwrap();
+        cancel_token.cancel();
+
+        match next_command(&mut receiver, &cancel_token).await {
+            NextCommand::Received(EditorCommand::Stop) => {}
+            _ => panic!("expected the queued command to win over cancellation"),
+        }
+    }
+
+    #[tokio::test]
+    async fn cancellation_wins_once_the_queue_is_empty() {
+        let (_sender, mut receiver) = mpsc::channel::<EditorCommand>(1);
+        let cancel_token = CancellationToken::new();
+        cancel_token.cancel();
+
+        match next_command(&mut receiver, &cancel_token).await {
+            NextCommand::Cancelled => {}
+            _ => panic!("expected cancellation once no command is queued"),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_closed_sender_is_reported_once_the_queue_is_empty() {
+        let (sender, mut receiver) = mpsc::channel::<EditorCommand>(1);
+        drop(sender);
+        let cancel_token = CancellationToken::new();
+
+        match next_command(&mut receiver, &cancel_token).await {
+            NextCommand::Closed => {}
+            _ => panic!("expected the closed channel to be reported"),
+        }
+    }
+}
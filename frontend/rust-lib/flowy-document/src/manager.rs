@@ -1,4 +1,12 @@
-use crate::{editor::ClientDocumentEditor, errors::FlowyError, DocumentCloudService};
+use crate::{
+    cursor::{CursorController, CursorState, CursorWSData},
+    editor::ClientDocumentEditor,
+    editor_worker::EditorWorker,
+    errors::FlowyError,
+    ws_worker::RevisionWSDataWorker,
+    workspace::{DocumentWorkspace, WorkspaceControlWSData},
+    DocumentCloudService,
+};
 use async_trait::async_trait;
 use bytes::Bytes;
 use dashmap::DashMap;
@@ -13,6 +21,7 @@ use flowy_sync::{RevisionCache, RevisionCloudService, RevisionManager, RevisionW
 use lib_infra::future::FutureResult;
 use lib_ws::WSConnectState;
 use std::{convert::TryInto, sync::Arc};
+use tokio_util::sync::CancellationToken;
 
 pub trait DocumentUser: Send + Sync {
     fn user_dir(&self) -> Result<String, FlowyError>;
@@ -24,6 +33,13 @@ pub trait DocumentUser: Send + Sync {
 #[async_trait]
 pub(crate) trait DocumentWSReceiver: Send + Sync {
     async fn receive_ws_data(&self, data: ServerRevisionWSData) -> Result<(), FlowyError>;
+    /// Applies a contiguous run of revisions fetched to fill a gap detected in the WS stream,
+    /// in place of replaying them as individual `receive_ws_data` calls.
+    async fn receive_revisions(&self, revisions: Vec<Revision>) -> Result<(), FlowyError>;
+    async fn receive_ws_cursor(&self, cursor: CursorState) -> Result<(), FlowyError>;
+    /// The local revision head, used to seed `RevisionWSDataWorker`'s gap-detection baseline.
+    /// `None` means "unknown", which the worker treats as trusting whatever frame arrives first.
+    fn current_rev_id(&self) -> Option<i64>;
     fn connect_state_changed(&self, state: WSConnectState);
 }
 type WebSocketDataReceivers = Arc<DashMap<String, Arc<dyn DocumentWSReceiver>>>;
@@ -33,6 +49,10 @@ pub struct FlowyDocumentManager {
     rev_web_socket: Arc<dyn RevisionWebSocket>,
     document_handlers: Arc<DocumentEditorHandlers>,
     document_user: Arc<dyn DocumentUser>,
+    cursor_controllers: Arc<DashMap<String, Arc<CursorController>>>,
+    rev_ws_workers: Arc<DashMap<String, Arc<RevisionWSDataWorker>>>,
+    cancel_tokens: Arc<DashMap<String, CancellationToken>>,
+    workspaces: Arc<DashMap<String, Arc<DocumentWorkspace>>>,
 }
 
 impl FlowyDocumentManager {
@@ -43,17 +63,29 @@ impl FlowyDocumentManager {
     ) -> Self {
         let ws_data_receivers = Arc::new(DashMap::new());
         let document_handlers = Arc::new(DocumentEditorHandlers::new());
+        let cursor_controllers = Arc::new(DashMap::new());
+        let rev_ws_workers = Arc::new(DashMap::new());
+        let cancel_tokens = Arc::new(DashMap::new());
+        let workspaces = Arc::new(DashMap::new());
         Self {
             cloud_service,
             ws_data_receivers,
             rev_web_socket,
             document_handlers,
             document_user,
+            cursor_controllers,
+            rev_ws_workers,
+            cancel_tokens,
+            workspaces,
         }
     }
 
     pub fn init(&self) -> FlowyResult<()> {
-        listen_ws_state_changed(self.rev_web_socket.clone(), self.ws_data_receivers.clone());
+        listen_ws_state_changed(
+            self.rev_web_socket.clone(),
+            self.ws_data_receivers.clone(),
+            self.cursor_controllers.clone(),
+        );
 
         Ok(())
     }
@@ -66,28 +98,47 @@ impl FlowyDocumentManager {
     }
 
     #[tracing::instrument(level = "trace", skip(self, doc_id), fields(doc_id), err)]
-    pub fn close_document<T: AsRef<str>>(&self, doc_id: T) -> Result<(), FlowyError> {
+    pub async fn close_document<T: AsRef<str>>(&self, doc_id: T) -> Result<(), FlowyError> {
         let doc_id = doc_id.as_ref();
         tracing::Span::current().record("doc_id", &doc_id);
-        self.document_handlers.remove(doc_id);
+        self.cancel_document_operations(doc_id);
+        self.document_handlers.remove(doc_id).await;
         self.ws_data_receivers.remove(doc_id);
+        self.cursor_controllers.remove(doc_id);
+        self.rev_ws_workers.remove(doc_id);
         Ok(())
     }
 
     #[tracing::instrument(level = "debug", skip(self, doc_id), fields(doc_id), err)]
-    pub fn delete<T: AsRef<str>>(&self, doc_id: T) -> Result<(), FlowyError> {
+    pub async fn delete<T: AsRef<str>>(&self, doc_id: T) -> Result<(), FlowyError> {
         let doc_id = doc_id.as_ref();
         tracing::Span::current().record("doc_id", &doc_id);
-        self.document_handlers.remove(doc_id);
+        self.cancel_document_operations(doc_id);
+        self.document_handlers.remove(doc_id).await;
         self.ws_data_receivers.remove(doc_id);
+        self.cursor_controllers.remove(doc_id);
+        self.rev_ws_workers.remove(doc_id);
         Ok(())
     }
 
+    /// Returns a broadcast stream of the other users' cursor/selection updates for `doc_id`.
+    /// The receiver only observes cursors received after the subscription is made.
+    pub fn subscribe_cursors<T: AsRef<str>>(&self, doc_id: T) -> tokio::sync::broadcast::Receiver<CursorState> {
+        let doc_id = doc_id.as_ref();
+        self.cursor_controllers
+            .entry(doc_id.to_string())
+            .or_insert_with(|| CursorController::new(doc_id))
+            .subscribe()
+    }
+
     #[tracing::instrument(level = "debug", skip(self, delta), fields(doc_id = %delta.doc_id), err)]
     pub async fn receive_local_delta(&self, delta: DocumentDelta) -> Result<DocumentDelta, FlowyError> {
-        let editor = self.get_editor(&delta.doc_id).await?;
-        let _ = editor.compose_local_delta(Bytes::from(delta.delta_json)).await?;
-        let document_json = editor.document_json().await?;
+        let _ = self.get_editor(&delta.doc_id).await?;
+        let worker = self.document_handlers.get_worker(&delta.doc_id).ok_or_else(|| {
+            FlowyError::record_not_found().context("Document editor worker was removed while composing")
+        })?;
+        let _ = worker.compose_local_delta(Bytes::from(delta.delta_json)).await?;
+        let document_json = worker.document_json().await?;
         Ok(DocumentDelta {
             doc_id: delta.doc_id.clone(),
             delta_json: document_json,
@@ -102,21 +153,79 @@ impl FlowyDocumentManager {
         Ok(())
     }
 
+    /// Hands the frame off to the `object_id`'s dedicated worker so frames for the same document
+    /// are applied one at a time in `rev_id` order, never interleaved with each other.
     pub async fn receive_ws_data(&self, data: Bytes) {
         let result: Result<ServerRevisionWSData, protobuf::ProtobufError> = data.try_into();
         match result {
             Ok(data) => match self.ws_data_receivers.get(&data.object_id) {
                 None => tracing::error!("Can't find any source handler for {:?}-{:?}", data.object_id, data.ty),
-                Some(handler) => match handler.receive_ws_data(data).await {
-                    Ok(_) => {}
-                    Err(e) => tracing::error!("{}", e),
-                },
+                Some(handler) => {
+                    let object_id = data.object_id.clone();
+                    let handler = handler.value().clone();
+                    let worker = match self.rev_ws_workers.get(&object_id) {
+                        Some(worker) => worker.clone(),
+                        None => match self.spawn_rev_ws_worker(&object_id, handler) {
+                            Ok(worker) => worker,
+                            Err(e) => {
+                                tracing::error!("{}", e);
+                                return;
+                            }
+                        },
+                    };
+                    if let Err(e) = worker.feed(data).await {
+                        tracing::error!("{}", e);
+                    }
+                }
             },
             Err(e) => {
                 tracing::error!("Document ws data parser failed: {:?}", e);
             }
         }
     }
+
+    /// Routes an inbound cursor/selection presence frame to the same per-document receiver that
+    /// handles revisions, and keeps the document's `CursorController` in sync.
+    pub async fn receive_ws_cursor_data(&self, data: CursorWSData) {
+        let CursorWSData { object_id, cursor } = data;
+        match self.ws_data_receivers.get(&object_id) {
+            None => tracing::error!("Can't find any source handler for cursor data on {:?}", object_id),
+            Some(handler) => match handler.receive_ws_cursor(cursor).await {
+                Ok(_) => {}
+                Err(e) => tracing::error!("{}", e),
+            },
+        }
+    }
+
+    /// The local user's id, exposed so `DocumentWorkspace` can stamp a `UserJoin`/`UserLeave`
+    /// event for the local user without reaching into `DocumentUser` itself.
+    pub(crate) fn user_id(&self) -> Result<String, FlowyError> {
+        self.document_user.user_id()
+    }
+
+    /// Registers `workspace` under its own `workspace_id` so `receive_ws_control_data` can find it
+    /// later. Called once from `DocumentWorkspace::new`, the same way a document registers its
+    /// worker in `ws_data_receivers` as soon as it's created.
+    pub(crate) fn register_workspace(&self, workspace: Arc<DocumentWorkspace>) {
+        self.workspaces.insert(workspace.workspace_id().to_string(), workspace);
+    }
+
+    /// Routes an inbound workspace-level control frame - `FileCreate`/`FileDelete`/`FileRename`/
+    /// `UserJoin`/`UserLeave` - to the `DocumentWorkspace` it's addressed to, the workspace-level
+    /// counterpart to `receive_ws_cursor_data`.
+    pub async fn receive_ws_control_data(&self, data: WorkspaceControlWSData) {
+        match self.workspaces.get(&data.workspace_id) {
+            None => tracing::error!("Can't find any workspace for control data on {:?}", data.workspace_id),
+            Some(workspace) => workspace.receive_control_event(data.event),
+        }
+    }
+
+    /// Drops every remote cursor belonging to `user_id` across all open documents. Called from
+    /// `DocumentWorkspace::receive_control_event`'s `UserLeave` handling, whether that event was
+    /// received over the websocket for a remote user or synthesized locally via `leave_as_local_user`.
+    pub fn remove_cursor_for_user(&self, user_id: &str) {
+        remove_cursor_for_user_in(&self.cursor_controllers, user_id);
+    }
 }
 
 impl FlowyDocumentManager {
@@ -137,29 +246,86 @@ impl FlowyDocumentManager {
     ) -> Result<Arc<ClientDocumentEditor>, FlowyError> {
         let user = self.document_user.clone();
         let token = self.document_user.token()?;
-        let rev_manager = self.make_rev_manager(doc_id, pool.clone())?;
+        let cancel_token = self.document_cancel_token(doc_id);
+        let rev_manager = self.make_rev_manager(doc_id, pool.clone(), cancel_token.clone())?;
         let cloud_service = Arc::new(DocumentRevisionCloudServiceImpl {
             token,
             server: self.cloud_service.clone(),
+            cancel_token: cancel_token.clone(),
         });
         let doc_editor =
             ClientDocumentEditor::new(doc_id, user, rev_manager, self.rev_web_socket.clone(), cloud_service).await?;
-        self.ws_data_receivers
-            .insert(doc_id.to_string(), doc_editor.ws_handler());
-        self.document_handlers.insert(doc_id, &doc_editor);
+        let cursor_controller = self
+            .cursor_controllers
+            .entry(doc_id.to_string())
+            .or_insert_with(|| CursorController::new(doc_id))
+            .clone();
+        let worker = Arc::new(EditorWorker::spawn(doc_id, doc_editor.clone(), cancel_token, cursor_controller));
+        self.ws_data_receivers.insert(doc_id.to_string(), worker.clone());
+        self.document_handlers.insert(doc_id, worker);
         Ok(doc_editor)
     }
 
-    fn make_rev_manager(&self, doc_id: &str, pool: Arc<ConnectionPool>) -> Result<RevisionManager, FlowyError> {
+    fn make_rev_manager(
+        &self,
+        doc_id: &str,
+        pool: Arc<ConnectionPool>,
+        cancel_token: CancellationToken,
+    ) -> Result<RevisionManager, FlowyError> {
         let user_id = self.document_user.user_id()?;
         let cache = Arc::new(RevisionCache::new(&user_id, doc_id, pool));
-        Ok(RevisionManager::new(&user_id, doc_id, cache))
+        Ok(RevisionManager::new(&user_id, doc_id, cache, cancel_token))
+    }
+
+    fn spawn_rev_ws_worker(
+        &self,
+        object_id: &str,
+        handler: Arc<dyn DocumentWSReceiver>,
+    ) -> Result<Arc<RevisionWSDataWorker>, FlowyError> {
+        let user_id = self.document_user.user_id()?;
+        let token = self.document_user.token()?;
+        let cancel_token = self.document_cancel_token(object_id);
+        let cloud_service = Arc::new(DocumentRevisionCloudServiceImpl {
+            token,
+            server: self.cloud_service.clone(),
+            cancel_token: cancel_token.clone(),
+        });
+        let initial_rev_id = handler.current_rev_id();
+        let worker = Arc::new(RevisionWSDataWorker::spawn(
+            object_id,
+            &user_id,
+            handler,
+            cloud_service,
+            cancel_token,
+            initial_rev_id,
+        ));
+        self.rev_ws_workers.insert(object_id.to_string(), worker.clone());
+        Ok(worker)
+    }
+
+    /// Returns the `CancellationToken` for `doc_id`, creating one if this is the first operation
+    /// kicked off for it since it was last closed.
+    fn document_cancel_token(&self, doc_id: &str) -> CancellationToken {
+        self.cancel_tokens
+            .entry(doc_id.to_string())
+            .or_insert_with(CancellationToken::new)
+            .clone()
+    }
+
+    /// Cancels the outstanding remote fetches for `doc_id` and makes its editor and revision
+    /// workers exit. A command already running or queued ahead of the cancellation still
+    /// completes; `reset_with_revisions` takes no token and is not cancelled.
+    fn cancel_document_operations(&self, doc_id: &str) {
+        if let Some((_, token)) = self.cancel_tokens.remove(doc_id) {
+            token.cancel();
+        }
     }
 }
 
 struct DocumentRevisionCloudServiceImpl {
     token: String,
     server: Arc<dyn DocumentCloudService>,
+    cancel_token: CancellationToken,
 }
 
 impl RevisionCloudService for DocumentRevisionCloudServiceImpl {
@@ -169,24 +335,65 @@ impl RevisionCloudService for DocumentRevisionCloudServiceImpl {
         let server = self.server.clone();
         let token = self.token.clone();
         let user_id = user_id.to_string();
+        let cancel_token = self.cancel_token.clone();
+
+        FutureResult::new(async move {
+            let fetch = async {
+                match server.read_document(&token, params).await? {
+                    None => Err(FlowyError::record_not_found().context("Remote doesn't have this document")),
+                    Some(doc) => {
+                        let delta_data = Bytes::from(doc.text.clone());
+                        let doc_md5 = md5(&delta_data);
+                        let revision =
+                            Revision::new(&doc.doc_id, doc.base_rev_id, doc.rev_id, delta_data, &user_id, doc_md5);
+                        Ok(vec![revision])
+                    }
+                }
+            };
+            tokio::select! {
+                result = fetch => result,
+                _ = cancel_token.cancelled() => Err(FlowyError::internal().context("fetch_object cancelled: document was closed")),
+            }
+        })
+    }
+
+    /// Fetches only the `[from_rev_id ..= to_rev_id]` window instead of the whole document, so a
+    /// client that is a few revisions behind doesn't pay for a full-document transfer and
+    /// md5-over-whole-doc on every reconnect.
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn fetch_revisions(
+        &self,
+        _user_id: &str,
+        object_id: &str,
+        from_rev_id: i64,
+        to_rev_id: i64,
+    ) -> FutureResult<Vec<Revision>, FlowyError> {
+        let params: DocumentId = object_id.to_string().into();
+        let server = self.server.clone();
+        let token = self.token.clone();
+        let cancel_token = self.cancel_token.clone();
 
         FutureResult::new(async move {
-            match server.read_document(&token, params).await? {
-                None => Err(FlowyError::record_not_found().context("Remote doesn't have this document")),
-                Some(doc) => {
-                    let delta_data = Bytes::from(doc.text.clone());
-                    let doc_md5 = md5(&delta_data);
-                    let revision =
-                        Revision::new(&doc.doc_id, doc.base_rev_id, doc.rev_id, delta_data, &user_id, doc_md5);
-                    Ok(vec![revision])
+            let fetch = async {
+                let revisions = server
+                    .fetch_document_revisions(&token, params, from_rev_id, to_rev_id)
+                    .await?;
+                if revisions.is_empty() {
+                    return Err(FlowyError::record_not_found()
+                        .context(format!("Remote has no revisions in [{}, {}]", from_rev_id, to_rev_id)));
                 }
+                Ok(revisions)
+            };
+            tokio::select! {
+                result = fetch => result,
+                _ = cancel_token.cancelled() => Err(FlowyError::internal().context("fetch_revisions cancelled: document was closed")),
             }
         })
     }
 }
 
 pub struct DocumentEditorHandlers {
-    inner: DashMap<String, Arc<ClientDocumentEditor>>,
+    inner: DashMap<String, Arc<EditorWorker>>,
 }
 
 impl DocumentEditorHandlers {
@@ -194,11 +401,11 @@ impl DocumentEditorHandlers {
         Self { inner: DashMap::new() }
     }
 
-    pub(crate) fn insert(&self, doc_id: &str, doc: &Arc<ClientDocumentEditor>) {
+    pub(crate) fn insert(&self, doc_id: &str, worker: Arc<EditorWorker>) {
         if self.inner.contains_key(doc_id) {
             log::warn!("Doc:{} already exists in cache", doc_id);
         }
-        self.inner.insert(doc_id.to_string(), doc.clone());
+        self.inner.insert(doc_id.to_string(), worker);
     }
 
     pub(crate) fn contains(&self, doc_id: &str) -> bool {
@@ -206,30 +413,85 @@ impl DocumentEditorHandlers {
     }
 
     pub(crate) fn get(&self, doc_id: &str) -> Option<Arc<ClientDocumentEditor>> {
-        if !self.contains(doc_id) {
-            return None;
-        }
-        let opened_doc = self.inner.get(doc_id).unwrap();
-        Some(opened_doc.clone())
+        self.get_worker(doc_id).map(|worker| worker.editor())
     }
 
-    pub(crate) fn remove(&self, id: &str) {
-        let doc_id = id.to_string();
-        if let Some(editor) = self.get(id) {
-            editor.stop()
+    pub(crate) fn get_worker(&self, doc_id: &str) -> Option<Arc<EditorWorker>> {
+        self.inner.get(doc_id).map(|worker| worker.clone())
+    }
+
+    /// Sends `Stop` to the editor's buffer worker and joins it, so the task has fully exited -
+    /// having processed every command already queued ahead of `Stop` - before this returns.
+    pub(crate) async fn remove(&self, id: &str) {
+        if let Some((_, worker)) = self.inner.remove(id) {
+            worker.stop().await;
         }
-        self.inner.remove(&doc_id);
     }
 }
 
-#[tracing::instrument(level = "trace", skip(web_socket, receivers))]
-fn listen_ws_state_changed(web_socket: Arc<dyn RevisionWebSocket>, receivers: WebSocketDataReceivers) {
+#[tracing::instrument(level = "trace", skip(web_socket, receivers, cursor_controllers))]
+fn listen_ws_state_changed(
+    web_socket: Arc<dyn RevisionWebSocket>,
+    receivers: WebSocketDataReceivers,
+    cursor_controllers: Arc<DashMap<String, Arc<CursorController>>>,
+) {
     tokio::spawn(async move {
         let mut notify = web_socket.subscribe_state_changed().await;
         while let Ok(state) = notify.recv().await {
             for receiver in receivers.iter() {
                 receiver.value().connect_state_changed(state.clone());
             }
+
+            if let WSConnectState::Disconnected = state {
+                // The socket no longer tells us which remote users were behind it, so drop every
+                // cursor rather than show a stale position until the next presence update.
+                for controller in cursor_controllers.iter() {
+                    controller.value().clear();
+                }
+            }
         }
     });
 }
+
+/// Pure logic behind `FlowyDocumentManager::remove_cursor_for_user`, extracted so it can be unit
+/// tested without constructing a full `FlowyDocumentManager`.
+fn remove_cursor_for_user_in(cursor_controllers: &DashMap<String, Arc<CursorController>>, user_id: &str) {
+    for controller in cursor_controllers.iter() {
+        controller.value().remove_user(user_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_cursor_for_user_in_drops_that_user_from_every_document() {
+        let controllers = DashMap::new();
+        let doc_a = CursorController::new("doc-a");
+        let doc_b = CursorController::new("doc-b");
+        doc_a.receive_cursor(
+            CursorState {
+                user_id: "user-1".to_owned(),
+                rev_id: 1,
+                range: crate::cursor::CursorRange { start: 0, end: 0 },
+            },
+            &[],
+        );
+        doc_b.receive_cursor(
+            CursorState {
+                user_id: "user-1".to_owned(),
+                rev_id: 1,
+                range: crate::cursor::CursorRange { start: 0, end: 0 },
+            },
+            &[],
+        );
+        controllers.insert("doc-a".to_string(), doc_a.clone());
+        controllers.insert("doc-b".to_string(), doc_b.clone());
+
+        remove_cursor_for_user_in(&controllers, "user-1");
+
+        assert!(!doc_a.contains_user("user-1"));
+        assert!(!doc_b.contains_user("user-1"));
+    }
+}
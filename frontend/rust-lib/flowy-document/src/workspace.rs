@@ -0,0 +1,232 @@
+use crate::{editor::ClientDocumentEditor, errors::FlowyError, manager::FlowyDocumentManager};
+use dashmap::DashSet;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+
+/// A shared environment grouping many documents: a filetree of `doc_id`s plus the set of users
+/// currently connected. Layered over `FlowyDocumentManager`, which still owns per-document
+/// revision traffic; the workspace only adds the aggregate view and the events that traffic
+/// doesn't carry on its own.
+pub struct DocumentWorkspace {
+    workspace_id: String,
+    document_manager: Arc<FlowyDocumentManager>,
+    filetree: RwLock<Vec<String>>,
+    connected_users: DashSet<String>,
+    connected_buffers: DashSet<String>,
+    notifier: broadcast::Sender<WorkspaceEvent>,
+}
+
+#[derive(Debug, Clone)]
+pub enum WorkspaceEvent {
+    FileCreate { doc_id: String },
+    FileDelete { doc_id: String },
+    FileRename { doc_id: String, name: String },
+    UserJoin { user_id: String },
+    UserLeave { user_id: String },
+}
+
+/// Sibling of `CursorWSData`: a workspace-level control frame received over the websocket, keyed
+/// by `workspace_id` the way a cursor frame is keyed by `object_id`, so `FlowyDocumentManager`'s
+/// `workspaces` registry can route it to the right `DocumentWorkspace`.
+#[derive(Debug, Clone)]
+pub struct WorkspaceControlWSData {
+    pub workspace_id: String,
+    pub event: WorkspaceEvent,
+}
+
+impl DocumentWorkspace {
+    /// Registers the new workspace with `document_manager` so `FlowyDocumentManager::receive_ws_control_data`
+    /// can find it by `workspace_id` - the workspace-level counterpart to how a document registers
+    /// its worker in `ws_data_receivers` as soon as it's opened.
+    pub fn new(workspace_id: &str, document_manager: Arc<FlowyDocumentManager>) -> Arc<Self> {
+        let (notifier, _) = broadcast::channel(100);
+        let workspace = Arc::new(Self {
+            workspace_id: workspace_id.to_owned(),
+            document_manager: document_manager.clone(),
+            filetree: RwLock::new(Vec::new()),
+            connected_users: DashSet::new(),
+            connected_buffers: DashSet::new(),
+            notifier,
+        });
+        document_manager.register_workspace(workspace.clone());
+        workspace
+    }
+
+    pub fn workspace_id(&self) -> &str {
+        &self.workspace_id
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<WorkspaceEvent> {
+        self.notifier.subscribe()
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, doc_id), fields(doc_id), err)]
+    pub async fn open_document<T: AsRef<str>>(&self, doc_id: T) -> Result<Arc<ClientDocumentEditor>, FlowyError> {
+        let doc_id = doc_id.as_ref();
+        tracing::Span::current().record("doc_id", &doc_id);
+        let editor = self.document_manager.open_document(doc_id).await?;
+        if self.connected_buffers.insert(doc_id.to_string()) {
+            self.receive_control_event(WorkspaceEvent::FileCreate { doc_id: doc_id.to_string() });
+        }
+        Ok(editor)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, doc_id), fields(doc_id), err)]
+    pub async fn close_document<T: AsRef<str>>(&self, doc_id: T) -> Result<(), FlowyError> {
+        let doc_id = doc_id.as_ref();
+        tracing::Span::current().record("doc_id", &doc_id);
+        self.document_manager.close_document(doc_id).await?;
+        self.connected_buffers.remove(doc_id);
+        Ok(())
+    }
+
+    /// Deletes `doc_id` from the document manager and the filetree, emitting `FileDelete` to
+    /// subscribers the same way a delete received over the websocket would.
+    #[tracing::instrument(level = "debug", skip(self, doc_id), fields(doc_id), err)]
+    pub async fn delete_document<T: AsRef<str>>(&self, doc_id: T) -> Result<(), FlowyError> {
+        let doc_id = doc_id.as_ref();
+        tracing::Span::current().record("doc_id", &doc_id);
+        self.document_manager.delete(doc_id).await?;
+        self.connected_buffers.remove(doc_id);
+        self.receive_control_event(WorkspaceEvent::FileDelete { doc_id: doc_id.to_string() });
+        Ok(())
+    }
+
+    /// Marks the local user as connected and emits `UserJoin`, mirroring how `open_document`
+    /// synthesizes `FileCreate` for a document the local user newly opens.
+    pub fn join_as_local_user(&self) -> Result<(), FlowyError> {
+        let user_id = self.document_manager.user_id()?;
+        self.receive_control_event(WorkspaceEvent::UserJoin { user_id });
+        Ok(())
+    }
+
+    /// Marks the local user as disconnected and emits `UserLeave`, the local-user counterpart to a
+    /// `UserLeave` control frame received over the websocket for a remote user.
+    pub fn leave_as_local_user(&self) -> Result<(), FlowyError> {
+        let user_id = self.document_manager.user_id()?;
+        self.receive_control_event(WorkspaceEvent::UserLeave { user_id });
+        Ok(())
+    }
+
+    /// Emits `FileRename` to subscribers. The filetree holds only `doc_id`s, so nothing in it
+    /// changes.
+    pub fn rename_document<T: AsRef<str>>(&self, doc_id: T, name: String) {
+        let doc_id = doc_id.as_ref().to_string();
+        self.receive_control_event(WorkspaceEvent::FileRename { doc_id, name });
+    }
+
+    /// Handles a workspace-level control frame — one not tied to a single `object_id`, e.g. a
+    /// `FileCreate`/`FileDelete`/`UserJoin`/`UserLeave` received over the websocket — and updates
+    /// the filetree/connected-user state before re-broadcasting it to subscribers. `UserLeave` also
+    /// drops that user's remote cursors across every open document, since a user who left the
+    /// workspace is no longer a valid cursor owner in any of them.
+    pub fn receive_control_event(&self, event: WorkspaceEvent) {
+        match &event {
+            WorkspaceEvent::FileCreate { doc_id } => self.track_file(doc_id),
+            WorkspaceEvent::FileDelete { doc_id } => self.untrack_file(doc_id),
+            WorkspaceEvent::FileRename { .. } => {}
+            WorkspaceEvent::UserJoin { .. } => apply_join_leave_in(&self.connected_users, &event),
+            WorkspaceEvent::UserLeave { user_id } => {
+                apply_join_leave_in(&self.connected_users, &event);
+                self.document_manager.remove_cursor_for_user(user_id);
+            }
+        }
+        let _ = self.notifier.send(event);
+    }
+
+    pub fn filetree(&self) -> Vec<String> {
+        self.filetree.read().unwrap().clone()
+    }
+
+    pub fn connected_users(&self) -> Vec<String> {
+        self.connected_users.iter().map(|u| u.clone()).collect()
+    }
+
+    fn track_file(&self, doc_id: &str) {
+        track_file_in(&mut self.filetree.write().unwrap(), doc_id);
+    }
+
+    fn untrack_file(&self, doc_id: &str) {
+        untrack_file_in(&mut self.filetree.write().unwrap(), doc_id);
+    }
+}
+
+/// Pure dedup logic behind `track_file`, extracted so it can be unit tested without constructing a
+/// `DocumentWorkspace` (which needs a full `FlowyDocumentManager`, cloud service, and websocket).
+fn track_file_in(filetree: &mut Vec<String>, doc_id: &str) {
+    if !filetree.iter().any(|id| id == doc_id) {
+        filetree.push(doc_id.to_string());
+    }
+}
+
+fn untrack_file_in(filetree: &mut Vec<String>, doc_id: &str) {
+    filetree.retain(|id| id != doc_id);
+}
+
+/// Pure logic behind the `UserJoin`/`UserLeave` arms of `receive_control_event`, extracted so the
+/// connected-user set's dedup/removal behavior can be unit tested without a full
+/// `DocumentWorkspace`.
+fn apply_join_leave_in(connected_users: &DashSet<String>, event: &WorkspaceEvent) {
+    match event {
+        WorkspaceEvent::UserJoin { user_id } => {
+            connected_users.insert(user_id.clone());
+        }
+        WorkspaceEvent::UserLeave { user_id } => {
+            connected_users.remove(user_id);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_join_is_idempotent_in_the_connected_set() {
+        let connected_users = DashSet::new();
+        apply_join_leave_in(&connected_users, &WorkspaceEvent::UserJoin { user_id: "user-1".to_owned() });
+        apply_join_leave_in(&connected_users, &WorkspaceEvent::UserJoin { user_id: "user-1".to_owned() });
+
+        assert_eq!(connected_users.len(), 1);
+        assert!(connected_users.contains("user-1"));
+    }
+
+    #[test]
+    fn user_leave_drops_only_that_user() {
+        let connected_users = DashSet::new();
+        apply_join_leave_in(&connected_users, &WorkspaceEvent::UserJoin { user_id: "user-1".to_owned() });
+        apply_join_leave_in(&connected_users, &WorkspaceEvent::UserJoin { user_id: "user-2".to_owned() });
+
+        apply_join_leave_in(&connected_users, &WorkspaceEvent::UserLeave { user_id: "user-1".to_owned() });
+
+        assert!(!connected_users.contains("user-1"));
+        assert!(connected_users.contains("user-2"));
+    }
+
+    #[test]
+    fn track_file_in_is_idempotent() {
+        let mut filetree = Vec::new();
+        track_file_in(&mut filetree, "doc-1");
+        track_file_in(&mut filetree, "doc-1");
+        track_file_in(&mut filetree, "doc-2");
+
+        assert_eq!(filetree, vec!["doc-1".to_string(), "doc-2".to_string()]);
+    }
+
+    #[test]
+    fn untrack_file_in_removes_only_the_matching_doc() {
+        let mut filetree = vec!["doc-1".to_string(), "doc-2".to_string()];
+        untrack_file_in(&mut filetree, "doc-1");
+
+        assert_eq!(filetree, vec!["doc-2".to_string()]);
+    }
+
+    #[test]
+    fn untrack_file_in_is_a_no_op_for_an_unknown_doc() {
+        let mut filetree = vec!["doc-1".to_string()];
+        untrack_file_in(&mut filetree, "doc-2");
+
+        assert_eq!(filetree, vec!["doc-1".to_string()]);
+    }
+}
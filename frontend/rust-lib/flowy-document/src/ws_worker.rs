@@ -0,0 +1,163 @@
+use crate::{errors::FlowyError, manager::DocumentWSReceiver};
+use flowy_collaboration::entities::ws_data::ServerRevisionWSData;
+use flowy_sync::RevisionCloudService;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Delivers every `ServerRevisionWSData` frame for a single `object_id` to its receiver, strictly
+/// in `rev_id` order. Frames for the same object are never processed concurrently, so a revision
+/// is fully applied before the next one starts.
+pub(crate) struct RevisionWSDataWorker {
+    sender: mpsc::Sender<ServerRevisionWSData>,
+}
+
+impl RevisionWSDataWorker {
+    /// `initial_rev_id` seeds the gap-detection baseline with the document's real local revision
+    /// head (`DocumentWSReceiver::current_rev_id`), taken at spawn time; the first frame expected
+    /// is the one right after that head. Leaving this `None` and
+    /// letting the baseline come from whichever frame arrives first would skip the gap check
+    /// entirely on the one case it matters most: a reconnect where the first frame observed is
+    /// already ahead of the true local head.
+    pub(crate) fn spawn(
+        object_id: &str,
+        user_id: &str,
+        handler: Arc<dyn DocumentWSReceiver>,
+        cloud_service: Arc<dyn RevisionCloudService>,
+        cancel_token: CancellationToken,
+        initial_rev_id: Option<i64>,
+    ) -> Self {
+        let (sender, mut receiver) = mpsc::channel(100);
+        let object_id = object_id.to_owned();
+        let user_id = user_id.to_owned();
+        tokio::spawn(async move {
+            let mut pending_by_rev_id: BTreeMap<i64, ServerRevisionWSData> = BTreeMap::new();
+            let mut next_rev_id = initial_rev_id.map(|rev_id| rev_id + 1);
+
+            loop {
+                // `biased` so a frame already enqueued ahead of cancellation is always drained before
+                // the cancellation branch can win the race, the same reasoning as `EditorWorker::spawn`.
+                let data = tokio::select! {
+                    biased;
+                    data = receiver.recv() => match data {
+                        None => break,
+                        Some(data) => data,
+                    },
+                    _ = cancel_token.cancelled() => {
+                        tracing::trace!("{} revision worker cancelled, exiting without applying further frames", object_id);
+                        break;
+                    }
+                };
+
+                match classify(next_rev_id, data.rev_id) {
+                    Step::Stale => continue,
+                    Step::InOrder => {}
+                    Step::Gap { from, to_inclusive } => {
+                        tracing::trace!(
+                            "{} gap: have {}, need {}..{}, fetching the missing range",
+                            object_id,
+                            from - 1,
+                            from,
+                            to_inclusive
+                        );
+                        match cloud_service.fetch_revisions(&user_id, &object_id, from, to_inclusive).await {
+                            Ok(missing) => {
+                                if let Err(e) = handler.receive_revisions(missing).await {
+                                    tracing::error!("{}", e);
+                                }
+                            }
+                            Err(e) => {
+                                // The missing range couldn't be fetched; buffer the frame and hope
+                                // a later gap-filling pull (or the next contiguous frame) resolves it.
+                                tracing::error!("{} failed to fetch missing revisions: {}", object_id, e);
+                                pending_by_rev_id.insert(data.rev_id, data);
+                                continue;
+                            }
+                        }
+                    }
+                }
+
+                next_rev_id = Some(data.rev_id + 1);
+                apply(&handler, data).await;
+
+                while let Some(expected) = next_rev_id {
+                    match pending_by_rev_id.remove(&expected) {
+                        None => break,
+                        Some(buffered) => {
+                            next_rev_id = Some(buffered.rev_id + 1);
+                            apply(&handler, buffered).await;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self { sender }
+    }
+
+    pub(crate) async fn feed(&self, data: ServerRevisionWSData) -> Result<(), FlowyError> {
+        self.sender
+            .send(data)
+            .await
+            .map_err(|_| FlowyError::internal().context("revision worker is no longer running"))
+    }
+}
+
+async fn apply(handler: &Arc<dyn DocumentWSReceiver>, data: ServerRevisionWSData) {
+    if let Err(e) = handler.receive_ws_data(data).await {
+        tracing::error!("{}", e);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    /// Already applied (or superseded by a pull); the frame should be dropped.
+    Stale,
+    /// Contiguous with the expected next revision; apply it directly.
+    InOrder,
+    /// Ahead of the expected next revision; `[from, to_inclusive]` is the missing range to fetch.
+    Gap { from: i64, to_inclusive: i64 },
+}
+
+/// Pure gap-detection decision, extracted from the worker loop so it can be unit tested without a
+/// running worker or a real `ServerRevisionWSData`. `expected` is `None` only when the baseline is
+/// genuinely unknown; every other case classifies `incoming_rev_id` against it.
+fn classify(expected: Option<i64>, incoming_rev_id: i64) -> Step {
+    match expected {
+        None => Step::InOrder,
+        Some(expected) if incoming_rev_id < expected => Step::Stale,
+        Some(expected) if incoming_rev_id > expected => Step::Gap {
+            from: expected,
+            to_inclusive: incoming_rev_id - 1,
+        },
+        Some(_) => Step::InOrder,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_baseline_trusts_the_first_frame() {
+        assert_eq!(classify(None, 42), Step::InOrder);
+    }
+
+    #[test]
+    fn contiguous_frame_is_in_order() {
+        assert_eq!(classify(Some(5), 5), Step::InOrder);
+    }
+
+    #[test]
+    fn already_applied_frame_is_stale() {
+        assert_eq!(classify(Some(5), 3), Step::Stale);
+    }
+
+    #[test]
+    fn ahead_of_expected_reports_the_missing_range() {
+        // This is the cold/reconnect case this fix targets: a real local head of 4 (so 5 is
+        // expected next) but the first frame observed after reconnecting is already at 10, so
+        // [5, 9] must be fetched.
+        assert_eq!(classify(Some(5), 10), Step::Gap { from: 5, to_inclusive: 9 });
+    }
+}